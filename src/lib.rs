@@ -1,10 +1,69 @@
 use zed_extension_api::{self as zed, settings::LspSettings, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt;
 use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 // Constants
 const PHPMD_CONFIG_FILES: &[&str] = &["phpmd.xml", "phpmd.xml.dist", ".phpmd.xml"];
+const USER_RULESET_PATH: &str = "phpmd-lsp/ruleset.xml";
+const COMBINED_RULESET_PREFIX: &str = "phpmd-combined-ruleset";
+const PROJECT_OVERRIDES_FILE: &str = ".zed/phpmd.json";
+const MACHINE_ONLY_SETTINGS: &[&str] = &[
+    "channel",
+    "localBinaryPath",
+    "phpmdPath",
+    "pharVersion",
+    "pharError",
+    "pharUpdateAvailable",
+    "singleFile",
+    "userRuleset",
+    "configTrace",
+    "projectOverridesError",
+];
+const QUARANTINE_ATTRIBUTE: &str = "com.apple.quarantine";
+// Version manager roots as (override env var, default location under HOME); shims live in `<root>/shims`
+const VERSION_MANAGER_ROOTS: &[(&str, &str)] = &[
+    ("ASDF_DATA_DIR", ".asdf"),
+    ("MISE_DATA_DIR", ".local/share/mise"),
+    ("PHPENV_ROOT", ".phpenv"),
+];
+const DEFAULT_RULESETS: &str = "cleancode,codesize,controversial,design,naming,unusedcode";
+const BUILTIN_RULESETS: &[&str] = &["cleancode", "codesize", "controversial", "design", "naming", "unusedcode"];
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+const RELEASES_REPOSITORY: &str = "GeneaLabs/zed-phpmd-lsp";
+const NIGHTLY_DIR_PREFIX: &str = "phpmd-nightly-";
+const PHPMD_REPOSITORY: &str = "phpmd/phpmd";
+const UPSTREAM_PHAR_DIR_PREFIX: &str = "phpmd-phar-";
+const PHAR_UPDATE_CHECK_FILE: &str = "phar-update-check.json";
+const PHAR_UPDATE_CHECK_INTERVAL_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Errors raised by the extension, converted to the plain `String` errors Zed expects at the API boundary.
+#[derive(Debug, PartialEq)]
+enum PhpmdLspError {
+    /// A release asset could not be downloaded, extracted, or made executable.
+    Download(String),
+    /// The extension was asked for something it is not configured to provide.
+    Config(String),
+}
+
+impl fmt::Display for PhpmdLspError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PhpmdLspError::Download(message) => write!(f, "Download error: {}", message),
+            PhpmdLspError::Config(message) => write!(f, "Configuration error: {}", message),
+        }
+    }
+}
+
+impl From<PhpmdLspError> for String {
+    fn from(error: PhpmdLspError) -> Self {
+        error.to_string()
+    }
+}
 
 struct PhpmdLspExtension {
     phpmd_lsp: Option<PhpmdLspServer>,
@@ -12,6 +71,75 @@ struct PhpmdLspExtension {
 
 struct PhpmdLspServer {
     cached_binary_path: Option<String>,
+    /// Release tag of the nightly build in use, so the matching PHAR can be fetched alongside it.
+    nightly_release: Option<String>,
+}
+
+/// Rulesets chosen for the server, plus the trace of every source that was consulted.
+struct RulesetResolution {
+    rulesets: String,
+    trace: Vec<ConfigTraceEntry>,
+    /// Ruleset refs to layer into a generated wrapper ruleset; empty when `rulesets` is used as-is.
+    merged_refs: Vec<String>,
+}
+
+/// Where the language server binary comes from, selected by the `channel` setting.
+#[derive(Debug, PartialEq)]
+enum BinaryChannel {
+    /// The release asset pinned to this extension's version.
+    Stable,
+    /// The newest GitHub pre-release, or the newest release when none is published.
+    Nightly,
+    /// A locally built binary.
+    Local(String),
+}
+
+impl BinaryChannel {
+    fn from_settings(settings: Option<&zed::serde_json::Value>) -> Result<Self, PhpmdLspError> {
+        let setting = |key: &str| settings.and_then(|settings| settings.get(key)).and_then(|value| value.as_str());
+        
+        match setting("channel").map(str::trim).unwrap_or("stable") {
+            "stable" | "" => Ok(BinaryChannel::Stable),
+            "nightly" => Ok(BinaryChannel::Nightly),
+            "local" => {
+                let path = setting("localBinaryPath")
+                    .map(str::trim)
+                    .filter(|path| !path.is_empty())
+                    .ok_or_else(|| PhpmdLspError::Config("The \"local\" channel requires localBinaryPath to point at a phpmd-lsp-server build".to_string()))?;
+                PhpmdLspExtension::reject_nul_bytes("localBinaryPath", path)?;
+                Ok(BinaryChannel::Local(path.to_string()))
+            },
+            other => Err(PhpmdLspError::Config(format!("Unknown channel \"{}\"; expected \"stable\", \"nightly\", or \"local\"", other))),
+        }
+    }
+}
+
+/// One step of the rulesets resolution, sent to the server as `configTrace`.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfigTraceEntry {
+    source: &'static str,
+    value: Option<String>,
+    selected: bool,
+}
+
+/// Where a PHAR is downloaded from.
+enum PharSource<'a> {
+    /// The gzipped asset attached to one of this extension's releases, by release tag.
+    Extension(&'a str),
+    /// The PHAR published with an upstream PHPMD release, by PHPMD version.
+    Upstream(&'a str),
+}
+
+/// Result of the last PHPMD release lookup, persisted so the check runs at most weekly.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PharUpdateCheck {
+    checked_at: u64,
+    latest_version: String,
+    /// Versions reported by `php phpmd.phar --version`, keyed by PHAR path.
+    #[serde(default)]
+    installed_versions: BTreeMap<String, String>,
 }
 
 impl PhpmdLspServer {
@@ -20,6 +148,7 @@ impl PhpmdLspServer {
     fn new() -> Self {
         Self {
             cached_binary_path: None,
+            nightly_release: None,
         }
     }
 
@@ -29,37 +158,96 @@ impl PhpmdLspServer {
         worktree: &zed::Worktree,
     ) -> Result<zed::Command> {
         let binary_path = self.language_server_binary_path(worktree)?;
+        
         Ok(zed::Command {
             command: binary_path,
             args: vec![],
-            env: Default::default(),
+            env: Self::server_env(worktree),
         })
     }
     
-    fn language_server_binary_path(&mut self, worktree: &zed::Worktree) -> Result<String> {
-        // Check if we have a cached binary path
-        if let Some(cached_path) = &self.cached_binary_path {
-            if fs::metadata(cached_path).is_ok() {
-                return Ok(cached_path.clone());
-            }
+    fn server_env(worktree: &zed::Worktree) -> Vec<(String, String)> {
+        let windows = matches!(zed::current_platform(), (zed::Os::Windows, _));
+        Self::env_with_shims_on_path(worktree.shell_env(), windows)
+    }
+    
+    fn env_with_shims_on_path(mut env: Vec<(String, String)>, windows: bool) -> Vec<(String, String)> {
+        // Hand the server the shell environment with version manager shims on PATH so it finds `php` too.
+        // Windows usually spells the variable `Path` and matches names case-insensitively, so update it in place.
+        let separator = if windows { ';' } else { ':' };
+        let path_name = env
+            .iter()
+            .map(|(name, _)| name)
+            .find(|name| if windows { name.eq_ignore_ascii_case("PATH") } else { *name == "PATH" })
+            .cloned()
+            .unwrap_or_else(|| "PATH".to_string());
+        let search_path = Self::join_search_path(&Self::search_path(&env, &path_name, separator), separator);
+        
+        match env.iter_mut().find(|(name, _)| *name == path_name) {
+            Some((_, value)) => *value = search_path,
+            None => env.push((path_name, search_path)),
         }
-
-        // Try to find the binary locally first (for development)
+        env
+    }
+    
+    fn language_server_binary_path(&mut self, worktree: &zed::Worktree) -> Result<String, PhpmdLspError> {
+        let settings = LspSettings::for_worktree(Self::LANGUAGE_SERVER_ID, worktree)
+            .ok()
+            .and_then(|lsp_settings| lsp_settings.settings);
         let binary_name = Self::get_platform_binary_name();
+        
+        match BinaryChannel::from_settings(settings.as_ref())? {
+            BinaryChannel::Stable => {},
+            BinaryChannel::Local(path) => {
+                // Relative paths point into the project, e.g. a checkout of this repository
+                let path = if PhpmdLspExtension::is_absolute_path(&path) {
+                    path
+                } else {
+                    std::path::Path::new(&worktree.root_path()).join(&path).to_string_lossy().to_string()
+                };
+                return worktree.which(&path).ok_or_else(|| {
+                    PhpmdLspError::Config(format!("localBinaryPath does not point at an executable file: {}", path))
+                });
+            },
+            BinaryChannel::Nightly => {
+                let latest = Self::latest_nightly_release().and_then(|release_tag| {
+                    let binary_path = self.download_binary(&binary_name, &release_tag, &Self::nightly_version_dir(&release_tag))?;
+                    Ok((release_tag, binary_path))
+                });
+                
+                // Keep working offline or when rate-limited with whichever nightly was installed last
+                let (release_tag, binary_path) = match latest {
+                    Ok(latest) => latest,
+                    Err(error) => Self::installed_nightly(&binary_name).ok_or(error)?,
+                };
+                
+                Self::remove_stale_nightlies(&release_tag);
+                self.nightly_release = Some(release_tag);
+                return Ok(binary_path);
+            },
+        }
+        
+        // Always re-resolve local installs so "editor: restart language server" picks up
+        // PATH or composer changes; only the downloaded binary is served from the cache
         if let Some(path) = worktree.which(&binary_name) {
-            self.cached_binary_path = Some(path.clone());
             return Ok(path);
         }
 
+        // Check if we have a cached download
+        if let Some(cached_path) = &self.cached_binary_path {
+            if fs::metadata(cached_path).is_ok() {
+                return Ok(cached_path.clone());
+            }
+        }
+
         // Download the binary from GitHub
-        let downloaded_path = self.download_binary(&binary_name)?;
+        let downloaded_path = self.download_binary(&binary_name, VERSION, &format!("phpmd-{}", VERSION))?;
         self.cached_binary_path = Some(downloaded_path.clone());
         Ok(downloaded_path)
     }
     
-    fn download_binary(&self, binary_name: &str) -> Result<String> {
+    fn download_binary(&self, binary_name: &str, release_tag: &str, version_dir: &str) -> Result<String, PhpmdLspError> {
         // Use the same pattern as Gleam extension
-        let version_dir = format!("phpmd-{}", VERSION);
         let binary_path = format!("{}/{}", version_dir, binary_name);
         
         // Check if binary already exists
@@ -76,44 +264,151 @@ impl PhpmdLspServer {
         let archive_name = format!("{}.{}", binary_name, archive_ext);
         
         let release_url = format!(
-            "https://github.com/GeneaLabs/zed-phpmd-lsp/releases/download/{}/{}",
-            VERSION,
+            "https://github.com/{}/releases/download/{}/{}",
+            RELEASES_REPOSITORY,
+            release_tag,
             archive_name
         );
         
-        
         // Try downloading from release
         let file_type = match os {
             zed::Os::Windows => zed::DownloadedFileType::Zip,
             _ => zed::DownloadedFileType::GzipTar,
         };
         
-        // Download the archive from release to version directory
-        zed::download_file(&release_url, &version_dir, file_type)
-            .map_err(|e| format!("Failed to download binary from release: {}. Please ensure the release {} exists with assets.", e, VERSION))?;
+        // Extract into a staging directory so an interrupted download never leaves a partial binary behind
+        let staging_dir = PhpmdLspExtension::staging_dir(version_dir, binary_name);
+        let staged_path = format!("{}/{}", staging_dir, binary_name);
+        fs::remove_dir_all(&staging_dir).ok();
         
-        // After extraction, the file should be in the bin directory
-        if !fs::metadata(&binary_path).is_ok() {
-            return Err(format!("Binary not found after extraction. Expected at: {}", binary_path));
+        // Download the archive from release to the staging directory
+        zed::download_file(&release_url, &staging_dir, file_type).map_err(|e| {
+            if binary_name.ends_with("-musl") {
+                PhpmdLspError::Download(format!("No musl build ({}) is available for release {}: {}. Install phpmd-lsp-server on your PATH or use a glibc-based environment.", archive_name, release_tag, e))
+            } else {
+                PhpmdLspError::Download(format!("Failed to download binary from release: {}. Please ensure the release {} exists with assets.", e, release_tag))
+            }
+        })?;
+        
+        // After extraction, the file should be in the staging directory
+        if fs::metadata(&staged_path).is_err() {
+            return Err(PhpmdLspError::Download(format!("Binary not found after extraction. Expected at: {}", staged_path)));
         }
         
         // Make the binary executable on Unix-like systems
         #[cfg(unix)]
         {
             use std::os::unix::fs::PermissionsExt;
-            if let Ok(metadata) = fs::metadata(&binary_path) {
+            if let Ok(metadata) = fs::metadata(&staged_path) {
                 let mut perms = metadata.permissions();
                 perms.set_mode(0o755);
-                fs::set_permissions(&binary_path, perms)
-                    .map_err(|e| format!("Failed to set binary permissions: {}", e))?;
+                fs::set_permissions(&staged_path, perms)
+                    .map_err(|e| PhpmdLspError::Download(format!("Failed to set binary permissions: {}", e)))?;
             }
         }
         
+        // Gatekeeper refuses to launch quarantined downloads on macOS
+        PhpmdLspExtension::clear_quarantine(&staged_path)?;
+        
+        PhpmdLspExtension::promote_download(&staging_dir, &staged_path, &binary_path)?;
+        
         Ok(binary_path)
     }
 
+    fn latest_nightly_release() -> Result<String, PhpmdLspError> {
+        // Zed only matches releases whose pre-release flag equals the one requested
+        [true, false]
+            .into_iter()
+            .map(|pre_release| {
+                zed::latest_github_release(
+                    RELEASES_REPOSITORY,
+                    zed::GithubReleaseOptions {
+                        require_assets: true,
+                        pre_release,
+                    },
+                )
+            })
+            .find_map(|release| release.ok())
+            .map(|release| release.version)
+            .ok_or_else(|| PhpmdLspError::Download(format!("Failed to look up the latest {} release", RELEASES_REPOSITORY)))
+    }
+    
+    fn nightly_version_dir(release_tag: &str) -> String {
+        format!("{}{}", NIGHTLY_DIR_PREFIX, release_tag)
+    }
+    
+    fn installed_nightly(binary_name: &str) -> Option<(String, String)> {
+        fs::read_dir(".")
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let release_tag = entry.file_name().to_str()?.strip_prefix(NIGHTLY_DIR_PREFIX)?.to_string();
+                let binary_path = format!("{}/{}", Self::nightly_version_dir(&release_tag), binary_name);
+                let modified = fs::metadata(&binary_path).and_then(|metadata| metadata.modified()).ok()?;
+                Some((modified, release_tag, binary_path))
+            })
+            .max_by_key(|(modified, _, _)| *modified)
+            .map(|(_, release_tag, binary_path)| (release_tag, binary_path))
+    }
+    
+    fn remove_stale_nightlies(current_release_tag: &str) {
+        let current_dir = Self::nightly_version_dir(current_release_tag);
+        let Ok(entries) = fs::read_dir(".") else {
+            return;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with(NIGHTLY_DIR_PREFIX) && name != current_dir {
+                fs::remove_dir_all(entry.path()).ok();
+            }
+        }
+    }
+
+    fn search_path(shell_env: &[(String, String)], path_name: &str, separator: char) -> Vec<std::path::PathBuf> {
+        let lookup = |key: &str| PhpmdLspExtension::shell_env_value(shell_env, key);
+
+        let dirs: Vec<std::path::PathBuf> = lookup(path_name)
+            .map(|path| {
+                path.split(separator)
+                    .filter(|dir| !dir.is_empty())
+                    .map(std::path::PathBuf::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Put version manager shims the shell PATH doesn't already include first, as an activated
+        // shell would, so a managed PHP wins over the system one
+        let home = lookup("HOME").or_else(|| lookup("USERPROFILE"));
+        let mut search_path = Vec::new();
+        for (root_var, default_root) in VERSION_MANAGER_ROOTS {
+            let root = lookup(root_var)
+                .map(std::path::PathBuf::from)
+                .or_else(|| home.as_ref().map(|home| std::path::PathBuf::from(home).join(default_root)));
+            if let Some(shims) = root.map(|root| root.join("shims")) {
+                if !dirs.contains(&shims) {
+                    search_path.push(shims);
+                }
+            }
+        }
+        search_path.extend(dirs);
+
+        search_path
+    }
+
+    fn join_search_path(dirs: &[std::path::PathBuf], separator: char) -> String {
+        dirs.iter()
+            .map(|dir| dir.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(&separator.to_string())
+    }
+
     fn get_platform_binary_name() -> String {
         let (os, arch) = zed::current_platform();
+        let musl = os == zed::Os::Linux && Self::is_musl_libc();
+        Self::platform_binary_name(os, arch, musl)
+    }
+
+    fn platform_binary_name(os: zed::Os, arch: zed::Architecture, musl: bool) -> String {
         match (os, arch) {
             (zed::Os::Windows, zed::Architecture::X8664) => "phpmd-lsp-server-windows-x64.exe".to_string(),
             (zed::Os::Windows, zed::Architecture::Aarch64) => "phpmd-lsp-server-windows-arm64.exe".to_string(),
@@ -121,11 +416,25 @@ impl PhpmdLspServer {
             (zed::Os::Mac, zed::Architecture::Aarch64) => "phpmd-lsp-server-macos-arm64".to_string(),
             (zed::Os::Mac, zed::Architecture::X8664) => "phpmd-lsp-server-macos-x64".to_string(),
             (zed::Os::Mac, _) => "phpmd-lsp-server".to_string(),
+            (zed::Os::Linux, zed::Architecture::X8664) if musl => "phpmd-lsp-server-linux-x64-musl".to_string(),
+            (zed::Os::Linux, zed::Architecture::Aarch64) if musl => "phpmd-lsp-server-linux-arm64-musl".to_string(),
             (zed::Os::Linux, zed::Architecture::X8664) => "phpmd-lsp-server-linux-x64".to_string(),
             (zed::Os::Linux, zed::Architecture::Aarch64) => "phpmd-lsp-server-linux-arm64".to_string(),
             (zed::Os::Linux, _) => "phpmd-lsp-server".to_string(),
         }
     }
+
+    fn is_musl_libc() -> bool {
+        // musl's ldd prints its banner to stderr, glibc's to stdout
+        zed::process::Command::new("ldd")
+            .arg("--version")
+            .output()
+            .map(|output| {
+                String::from_utf8_lossy(&output.stdout).contains("musl")
+                    || String::from_utf8_lossy(&output.stderr).contains("musl")
+            })
+            .unwrap_or(false)
+    }
 }
 
 impl zed::Extension for PhpmdLspExtension {
@@ -146,7 +455,7 @@ impl zed::Extension for PhpmdLspExtension {
                 phpmd_lsp.language_server_command(language_server_id, worktree)
             }
             language_server_id => {
-                Err(format!("unknown language server: {language_server_id}").into())
+                Err(PhpmdLspError::Config(format!("unknown language server: {language_server_id}")).into())
             }
         }
     }
@@ -160,74 +469,149 @@ impl zed::Extension for PhpmdLspExtension {
         if language_server_id.as_ref() != PhpmdLspServer::LANGUAGE_SERVER_ID {
             return Ok(None);
         }
-        let mut options = zed::serde_json::Map::new();
+        // Team-shared overrides committed to the repo form the base layer; a broken file is reported, not fatal
+        let (project_overrides, project_overrides_error) = match worktree.read_text_file(PROJECT_OVERRIDES_FILE) {
+            Ok(contents) => match Self::parse_project_overrides(&contents) {
+                Ok(overrides) => (overrides, None),
+                Err(error) => (zed::serde_json::Map::new(), Some(error.to_string())),
+            },
+            Err(_) => (zed::serde_json::Map::new(), None),
+        };
+        let project_settings = (!project_overrides.is_empty()).then(|| zed::serde_json::Value::Object(project_overrides.clone()));
         
         // Try to get user-configured settings first
         let user_settings = LspSettings::for_worktree(language_server_id.as_ref(), worktree)
             .ok()
             .and_then(|lsp_settings| lsp_settings.settings.clone());
         
-        // Download PHPMD PHAR to LSP server directory - LSP server will find it automatically
-        Self::download_phar_if_needed("phpmd.phar").ok();
-        
-        // Determine rulesets to use (priority order: config file -> settings -> env -> default)
-        let mut rulesets_to_use: Option<String> = None;
-        
-        // Try to find phpmd configuration file first (highest priority)
-        if let Some(config_file) = Self::find_phpmd_config(worktree) {
-            rulesets_to_use = Some(config_file);
-        }
-        
-        // Check for user-configured rulesets from settings.json
-        if rulesets_to_use.is_none() {
-            if let Some(settings) = user_settings.as_ref() {
-                // Support both string and array formats for rulesets
-                if let Some(rulesets_value) = settings.get("rulesets") {
-                    match rulesets_value {
-                        // Single ruleset as string
-                        zed::serde_json::Value::String(rulesets) => {
-                            if !rulesets.trim().is_empty() {
-                                rulesets_to_use = Some(rulesets.clone());
-                            }
-                        },
-                        // Multiple rulesets as array
-                        zed::serde_json::Value::Array(rulesets) => {
-                            let ruleset_strings: Vec<String> = rulesets
-                                .iter()
-                                .filter_map(|v| v.as_str())
-                                .filter(|s| !s.trim().is_empty())
-                                .map(|s| s.to_string())
-                                .collect();
-                            
-                            if !ruleset_strings.is_empty() {
-                                let combined_rulesets = ruleset_strings.join(",");
-                                rulesets_to_use = Some(combined_rulesets);
-                            }
-                        },
-                        _ => {}
+        // User settings win over the committed project overrides
+        let mut options = Self::merge_settings(project_overrides, user_settings.as_ref());
+        let effective_settings = (!options.is_empty()).then(|| zed::serde_json::Value::Object(options.clone()));
+        if let Some(error) = project_overrides_error {
+            options.insert("projectOverridesError".to_string(), zed::serde_json::Value::String(error));
+        }
+        
+        // A pinned upstream PHPMD release replaces the bundled PHAR, unless phpmdPath already names one
+        let phar_version = effective_settings
+            .as_ref()
+            .and_then(|settings| settings.get("pharVersion"))
+            .and_then(|value| value.as_str())
+            .map(|version| version.trim().trim_start_matches('v'))
+            .filter(|version| !version.is_empty());
+        
+        // Otherwise download PHPMD PHAR next to the server binary of the selected channel - LSP server will find it automatically
+        let phar_release = match BinaryChannel::from_settings(user_settings.as_ref()) {
+            Ok(BinaryChannel::Stable) => Some((VERSION.to_string(), format!("phpmd-{}", VERSION))),
+            Ok(BinaryChannel::Nightly) => self
+                .phpmd_lsp
+                .as_ref()
+                .and_then(|phpmd_lsp| phpmd_lsp.nightly_release.clone())
+                .map(|release_tag| {
+                    let version_dir = PhpmdLspServer::nightly_version_dir(&release_tag);
+                    (release_tag, version_dir)
+                }),
+            Ok(BinaryChannel::Local(_)) | Err(_) => None,
+        };
+        let mut installed_phar = None;
+        if let (None, Some((release_tag, version_dir))) = (phar_version, phar_release) {
+            // The server can still use another PHPMD install, so report the failure instead of aborting startup
+            match Self::download_phar_if_needed("phpmd.phar", PharSource::Extension(&release_tag), &version_dir) {
+                Ok(phar_path) => installed_phar = Some(phar_path),
+                Err(error) => {
+                    options.insert("pharError".to_string(), zed::serde_json::Value::String(error.to_string()));
+                },
+            }
+        }
+        
+        if let Some(phar_version) = phar_version {
+            if !Self::is_release_version(phar_version) {
+                return Err(PhpmdLspError::Config(format!("pharVersion \"{}\" is not a PHPMD release version such as \"2.15.0\"", phar_version)).into());
+            }
+            let version_dir = format!("{}{}", UPSTREAM_PHAR_DIR_PREFIX, phar_version);
+            match Self::download_phar_if_needed("phpmd.phar", PharSource::Upstream(phar_version), &version_dir) {
+                Ok(phar_path) => {
+                    if !options.contains_key("phpmdPath") {
+                        if let Some(absolute_path) = env::current_dir().ok().and_then(|dir| dir.join(&phar_path).to_str().map(|path| path.to_string())) {
+                            options.insert("phpmdPath".to_string(), zed::serde_json::Value::String(absolute_path));
+                        }
                     }
-                }
+                    installed_phar = Some(phar_path);
+                },
+                Err(error) => {
+                    options.insert("pharError".to_string(), zed::serde_json::Value::String(error.to_string()));
+                },
             }
         }
         
-        // Fall back to environment variable for rulesets
-        if rulesets_to_use.is_none() {
-            if let Ok(env_rulesets) = env::var("PHPMD_RULESETS") {
-                if !env_rulesets.trim().is_empty() {
-                    rulesets_to_use = Some(env_rulesets);
+        // Opt-in check for a newer PHPMD release than the installed PHAR
+        let check_phar_updates = effective_settings
+            .as_ref()
+            .and_then(|settings| settings.get("checkPharUpdates"))
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false);
+        if check_phar_updates {
+            let versions = installed_phar
+                .as_deref()
+                .and_then(|phar_path| Self::phar_update_versions(phar_path, || PhpmdLspServer::server_env(worktree)));
+            if let Some((installed_version, latest_version)) = versions {
+                if Self::is_newer_version(&latest_version, &installed_version) {
+                    options.insert("pharUpdateAvailable".to_string(), zed::serde_json::json!({
+                        "installedVersion": installed_version,
+                        "latestVersion": latest_version,
+                        "updateSetting": { "pharVersion": latest_version },
+                    }));
                 }
             }
         }
         
-        // If still no rulesets, use sensible defaults
-        if rulesets_to_use.is_none() {
-            // Default to common rulesets
-            rulesets_to_use = Some("cleancode,codesize,controversial,design,naming,unusedcode".to_string());
+        // Determine rulesets to use (priority order: config file -> settings -> project overrides -> env -> default)
+        // A single opened file has no project root to discover configs in
+        let single_file = Self::is_single_file_worktree(std::path::Path::new(&worktree.root_path()));
+        if single_file {
+            options.insert("singleFile".to_string(), zed::serde_json::Value::Bool(true));
         }
+        let config_file = if single_file { None } else { Self::find_phpmd_config(worktree) };
+        
+        let env_rulesets = env::var("PHPMD_RULESETS").ok();
+        let mut resolution = Self::resolve_rulesets(
+            config_file,
+            user_settings.as_ref(),
+            project_settings.as_ref(),
+            env_rulesets,
+        )?;
         
-        // Pass the rulesets to the LSP server
-        if let Some(rulesets) = rulesets_to_use {
-            options.insert("rulesets".to_string(), zed::serde_json::Value::String(rulesets.clone()));
+        // Validate the user-supplied rulesets actually in use before they reach the PHPMD command line; a
+        // single file has no worktree to check them against, so those pass through for the server to report
+        let worktree_root = std::path::PathBuf::from(worktree.root_path());
+        let project_root = match (single_file, worktree_root.parent()) {
+            (true, Some(parent)) => parent.to_path_buf(),
+            _ => worktree_root,
+        };
+        let is_project_file = |path: &str| single_file || worktree.read_text_file(path).is_ok();
+        Self::sanitize_resolution(&mut resolution, &project_root, is_project_file)?;
+        
+        // Combine the project config with any layered rulesets into a generated wrapper
+        if !resolution.merged_refs.is_empty() {
+            if let Some(combined_path) = Self::write_combined_ruleset(worktree.id(), &resolution.merged_refs) {
+                resolution.trace.push(ConfigTraceEntry {
+                    source: "combinedRuleset",
+                    value: Some(combined_path.clone()),
+                    selected: true,
+                });
+                resolution.rulesets = combined_path;
+            }
+        }
+        
+        // Pass the rulesets to the LSP server, along with how they were chosen
+        options.insert("rulesets".to_string(), zed::serde_json::Value::String(resolution.rulesets));
+        
+        // The sandbox cannot see the user's config directory, so the server checks for the personal ruleset
+        let windows = matches!(zed::current_platform(), (zed::Os::Windows, _));
+        if let Some(user_ruleset) = Self::user_ruleset_path(&worktree.shell_env(), windows) {
+            options.insert("userRuleset".to_string(), zed::serde_json::Value::String(user_ruleset.to_string_lossy().to_string()));
+        }
+        if let Ok(trace) = zed::serde_json::to_value(&resolution.trace) {
+            options.insert("configTrace".to_string(), trace);
         }
         
         if options.is_empty() {
@@ -241,9 +625,288 @@ impl zed::Extension for PhpmdLspExtension {
 
 impl PhpmdLspExtension {
     
-    fn download_phar_if_needed(phar_name: &str) -> Result<String> {
+    fn parse_project_overrides(contents: &str) -> Result<zed::serde_json::Map<String, zed::serde_json::Value>, PhpmdLspError> {
+        match zed::serde_json::from_str(contents) {
+            Ok(zed::serde_json::Value::Object(mut overrides)) => {
+                // Settings that pick or describe what runs on this machine only ever come from the user's own Zed settings
+                overrides.retain(|key, _| !MACHINE_ONLY_SETTINGS.contains(&key.as_str()));
+                Ok(overrides)
+            },
+            Ok(_) => Err(PhpmdLspError::Config(format!("{} must contain a JSON object", PROJECT_OVERRIDES_FILE))),
+            Err(e) => Err(PhpmdLspError::Config(format!("Failed to parse {}: {}", PROJECT_OVERRIDES_FILE, e))),
+        }
+    }
+    
+    fn merge_settings(
+        mut base: zed::serde_json::Map<String, zed::serde_json::Value>,
+        user_settings: Option<&zed::serde_json::Value>,
+    ) -> zed::serde_json::Map<String, zed::serde_json::Value> {
+        if let Some(zed::serde_json::Value::Object(user_settings)) = user_settings {
+            for (key, value) in user_settings {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+        
+        base
+    }
+    
+    fn settings_rulesets(settings: Option<&zed::serde_json::Value>) -> Option<String> {
+        // Support string, array, and toggle-map formats for rulesets in settings.json
+        settings
+            .and_then(|settings| settings.get("rulesets"))
+            .and_then(|rulesets_value| match rulesets_value {
+                // Single ruleset as string
+                zed::serde_json::Value::String(rulesets) if Self::has_ruleset_entries(rulesets) => {
+                    Some(rulesets.clone())
+                },
+                // Multiple rulesets as array
+                zed::serde_json::Value::Array(rulesets) => {
+                    let ruleset_strings: Vec<&str> = rulesets
+                        .iter()
+                        .filter_map(|v| v.as_str())
+                        .filter(|s| !s.trim().is_empty())
+                        .collect();
+                    
+                    if ruleset_strings.is_empty() {
+                        None
+                    } else {
+                        Some(ruleset_strings.join(","))
+                    }
+                },
+                // Per-ruleset toggles, e.g. { "cleancode": true, "controversial": false }
+                zed::serde_json::Value::Object(toggles) => {
+                    let enabled_rulesets: Vec<&str> = toggles
+                        .iter()
+                        .filter(|(_, enabled)| enabled.as_bool() == Some(true))
+                        .map(|(name, _)| name.trim())
+                        .filter(|name| !name.is_empty())
+                        .collect();
+
+                    if enabled_rulesets.is_empty() {
+                        None
+                    } else {
+                        Some(enabled_rulesets.join(","))
+                    }
+                },
+                _ => None,
+            })
+    }
+    
+    fn sanitize_rulesets(
+        rulesets: &str,
+        project_root: &std::path::Path,
+        is_project_file: impl Fn(&str) -> bool,
+    ) -> Result<String, PhpmdLspError> {
+        let mut sanitized = Vec::new();
+        // Empty entries, e.g. from a trailing comma, are simply dropped
+        for entry in rulesets.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            Self::reject_nul_bytes("Ruleset", entry)?;
+            
+            // Built-in rulesets are resolved by PHPMD itself, and the sandbox cannot check paths
+            // outside the worktree, so absolute and parent-relative paths are left to the server
+            let path = std::path::Path::new(entry);
+            let leaves_worktree = path.components().any(|component| component == std::path::Component::ParentDir);
+            if BUILTIN_RULESETS.contains(&entry) || entry.starts_with("rulesets/") || Self::is_absolute_path(entry) || leaves_worktree {
+                sanitized.push(entry.to_string());
+                continue;
+            }
+            
+            // Anything else is a ruleset file inside the project
+            if !is_project_file(entry) {
+                return Err(PhpmdLspError::Config(format!(
+                    "Ruleset file \"{}\" does not exist in the project. Use a built-in ruleset ({}) or a path relative to the project root.",
+                    entry,
+                    BUILTIN_RULESETS.join(", ")
+                )));
+            }
+            sanitized.push(project_root.join(path).to_string_lossy().to_string());
+        }
+        
+        Ok(sanitized.join(","))
+    }
+    
+    fn sanitize_resolution(
+        resolution: &mut RulesetResolution,
+        project_root: &std::path::Path,
+        is_project_file: impl Fn(&str) -> bool,
+    ) -> Result<(), PhpmdLspError> {
+        // Only the selected or merged source is used, so rulesets from unused sources are never checked
+        let in_use = resolution
+            .trace
+            .iter_mut()
+            .find(|entry| entry.selected && matches!(entry.source, "settings" | "projectOverrides" | "env"));
+        let Some(value) = in_use.and_then(|entry| entry.value.as_mut()) else {
+            return Ok(());
+        };
+        *value = Self::sanitize_rulesets(value, project_root, is_project_file)?;
+        
+        // Layered rulesets sit in front of the project config, which stays last
+        match resolution.merged_refs.pop() {
+            Some(project_ruleset) => {
+                resolution.merged_refs = value.split(',').map(Self::ruleset_ref).collect();
+                resolution.merged_refs.push(project_ruleset);
+            },
+            None => resolution.rulesets = value.clone(),
+        }
+        
+        Ok(())
+    }
+    
+    fn has_ruleset_entries(rulesets: &str) -> bool {
+        rulesets.split(',').any(|entry| !entry.trim().is_empty())
+    }
+    
+    fn reject_nul_bytes(setting: &str, value: &str) -> Result<(), PhpmdLspError> {
+        if value.contains('\0') {
+            return Err(PhpmdLspError::Config(format!("{} {:?} contains a NUL byte", setting, value)));
+        }
+        
+        Ok(())
+    }
+    
+    fn is_absolute_path(path: &str) -> bool {
+        // The extension always sees Unix-style paths, but Windows hosts hand it drive and UNC paths
+        let bytes = path.as_bytes();
+        path.starts_with('/')
+            || path.starts_with('\\')
+            || (bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && (bytes[2] == b'\\' || bytes[2] == b'/'))
+    }
+    
+    fn resolve_rulesets(
+        config_file: Option<String>,
+        settings: Option<&zed::serde_json::Value>,
+        project_overrides: Option<&zed::serde_json::Value>,
+        env_rulesets: Option<String>,
+    ) -> Result<RulesetResolution, PhpmdLspError> {
+        let settings_rulesets = Self::settings_rulesets(settings);
+        let project_overrides_rulesets = Self::settings_rulesets(project_overrides);
+        let env_rulesets = env_rulesets.filter(|rulesets| Self::has_ruleset_entries(rulesets));
+        
+        // Record every source in priority order; the first one with a value wins
+        let candidates = [
+            ("configFile", config_file),
+            ("settings", settings_rulesets),
+            ("projectOverrides", project_overrides_rulesets),
+            ("env", env_rulesets),
+            ("default", Some(DEFAULT_RULESETS.to_string())),
+        ];
+        
+        let mut rulesets = None;
+        let mut trace: Vec<ConfigTraceEntry> = Vec::with_capacity(candidates.len());
+        for (source, value) in candidates {
+            let selected = rulesets.is_none() && value.is_some();
+            if selected {
+                rulesets = value.clone();
+            }
+            trace.push(ConfigTraceEntry { source, value, selected });
+        }
+        
+        // A project config replaces settings rulesets unless settings opt in to layering them underneath it
+        let rulesets_mode = settings
+            .and_then(|settings| settings.get("rulesetsMode"))
+            .or_else(|| project_overrides.and_then(|overrides| overrides.get("rulesetsMode")));
+        let merge_settings = match rulesets_mode {
+            None | Some(zed::serde_json::Value::Null) => false,
+            Some(mode) => match mode.as_str() {
+                Some("replace") => false,
+                Some("merge") => true,
+                _ => return Err(PhpmdLspError::Config(format!("Unknown rulesetsMode {}; expected \"replace\" or \"merge\"", mode))),
+            },
+        };
+        let mut merged_refs = Vec::new();
+        if let (true, true, Some(project_ruleset)) = (trace[0].selected, merge_settings, &rulesets) {
+            let layered = trace
+                .iter_mut()
+                .find(|entry| (entry.source == "settings" || entry.source == "projectOverrides") && entry.value.is_some());
+            if let Some(settings_entry) = layered {
+                if let Some(value) = &settings_entry.value {
+                    merged_refs.extend(value.split(',').filter(|entry| !entry.trim().is_empty()).map(Self::ruleset_ref));
+                    merged_refs.push(project_ruleset.clone());
+                    settings_entry.selected = true;
+                }
+            }
+        }
+        
+        Ok(RulesetResolution {
+            rulesets: rulesets.unwrap_or_else(|| DEFAULT_RULESETS.to_string()),
+            trace,
+            merged_refs,
+        })
+    }
+    
+    fn ruleset_ref(ruleset: &str) -> String {
+        // Built-in ruleset names need PHPMD's internal path when referenced from a ruleset file
+        let ruleset = ruleset.trim();
+        if BUILTIN_RULESETS.contains(&ruleset) {
+            format!("rulesets/{}.xml", ruleset)
+        } else {
+            ruleset.to_string()
+        }
+    }
+    
+    fn user_ruleset_path(shell_env: &[(String, String)], windows: bool) -> Option<std::path::PathBuf> {
+        let lookup = |key: &str| Self::shell_env_value(shell_env, key).map(std::path::PathBuf::from);
+        
+        // Windows keeps per-user configuration in %APPDATA%; elsewhere follow XDG conventions, falling back to ~/.config
+        let config_dir = if windows {
+            lookup("APPDATA")?
+        } else {
+            lookup("XDG_CONFIG_HOME").or_else(|| lookup("HOME").map(|home| home.join(".config")))?
+        };
+        
+        Some(config_dir.join(USER_RULESET_PATH))
+    }
+    
+    fn shell_env_value(shell_env: &[(String, String)], key: &str) -> Option<String> {
+        shell_env
+            .iter()
+            .find(|(name, value)| name == key && !value.trim().is_empty())
+            .map(|(_, value)| value.clone())
+    }
+    
+    fn combined_ruleset_xml(refs: &[String]) -> String {
+        let escape = |value: &str| {
+            value
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        };
+        
+        let rule_refs: String = refs
+            .iter()
+            .map(|rule_ref| format!("    <rule ref=\"{}\"/>\n", escape(rule_ref)))
+            .collect();
+        
+        format!(
+            concat!(
+                "<?xml version=\"1.0\"?>\n",
+                "<ruleset name=\"PHPMD LSP combined ruleset\"\n",
+                "         xmlns=\"http://pmd.sf.net/ruleset/1.0.0\"\n",
+                "         xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\"\n",
+                "         xsi:schemaLocation=\"http://pmd.sf.net/ruleset/1.0.0 http://pmd.sf.net/ruleset_xml_schema.xsd\">\n",
+                "    <description>Generated by the PHPMD Zed extension. Do not edit.</description>\n",
+                "{}",
+                "</ruleset>\n",
+            ),
+            rule_refs
+        )
+    }
+    
+    fn write_combined_ruleset(worktree_id: u64, refs: &[String]) -> Option<String> {
+        // The server runs outside the extension sandbox, so hand it an absolute path; the work
+        // directory is shared by every open project, so each worktree gets its own file
+        let combined_path = env::current_dir().ok()?.join(Self::combined_ruleset_file(worktree_id));
+        Self::write_atomically(&combined_path, &Self::combined_ruleset_xml(refs)).ok()?;
+        combined_path.to_str().map(|path| path.to_string())
+    }
+    
+    fn combined_ruleset_file(worktree_id: u64) -> String {
+        format!("{}-{}.xml", COMBINED_RULESET_PREFIX, worktree_id)
+    }
+    
+    fn download_phar_if_needed(phar_name: &str, source: PharSource, version_dir: &str) -> Result<String, PhpmdLspError> {
         // Use the same pattern as Gleam extension for consistency
-        let version_dir = format!("phpmd-{}", VERSION);
         let phar_path = format!("{}/{}", version_dir, phar_name);
         
         // Check if PHAR already exists
@@ -251,54 +914,245 @@ impl PhpmdLspExtension {
             return Ok(phar_path);
         }
         
-        // Try to download from release assets first
-        let archive_name = format!("{}.tar.gz", phar_name);
+        // Extension releases ship the PHAR gzipped, upstream PHPMD releases attach it as-is
+        let (repository, release_tag, asset_name) = match source {
+            PharSource::Extension(release_tag) => (RELEASES_REPOSITORY, release_tag, format!("{}.tar.gz", phar_name)),
+            PharSource::Upstream(version) => (PHPMD_REPOSITORY, version, phar_name.to_string()),
+        };
         
         let release_url = format!(
-            "https://github.com/GeneaLabs/zed-phpmd-lsp/releases/download/{}/{}",
-            VERSION,
-            archive_name
+            "https://github.com/{}/releases/download/{}/{}",
+            repository,
+            release_tag,
+            asset_name
         );
         
-        // Download the archive from release to version directory
-        zed::download_file(&release_url, &version_dir, zed::DownloadedFileType::GzipTar)
-            .map_err(|e| format!("Failed to download {} from release: {}. Please ensure the release {} exists with assets.", phar_name, e, VERSION))?;
+        // Extract into a staging directory so an interrupted download never leaves a partial PHAR behind
+        let staging_dir = Self::staging_dir(version_dir, phar_name);
+        let staged_path = format!("{}/{}", staging_dir, phar_name);
+        fs::remove_dir_all(&staging_dir).ok();
+        
+        // Download the asset from release to the staging directory
+        let downloaded = match source {
+            PharSource::Extension(_) => zed::download_file(&release_url, &staging_dir, zed::DownloadedFileType::GzipTar),
+            PharSource::Upstream(_) => fs::create_dir_all(&staging_dir)
+                .map_err(|e| e.to_string())
+                .and_then(|_| zed::download_file(&release_url, &staged_path, zed::DownloadedFileType::Uncompressed)),
+        };
+        downloaded.map_err(|e| PhpmdLspError::Download(format!("Failed to download {} from release: {}. Please ensure the {} release {} exists with assets.", phar_name, e, repository, release_tag)))?;
         
-        // After extraction, the file should be in the bin directory
-        if !fs::metadata(&phar_path).is_ok() {
-            return Err(format!("{} not found after extraction. Expected at: {}", phar_name, phar_path));
+        // After extraction, the file should be in the staging directory
+        if fs::metadata(&staged_path).is_err() {
+            return Err(PhpmdLspError::Download(format!("{} not found after extraction. Expected at: {}", phar_name, staged_path)));
         }
         
         // Make the PHAR executable on Unix-like systems
         #[cfg(unix)]
         {
             use std::os::unix::fs::PermissionsExt;
-            if let Ok(metadata) = fs::metadata(&phar_path) {
+            if let Ok(metadata) = fs::metadata(&staged_path) {
                 let mut perms = metadata.permissions();
                 perms.set_mode(0o755);
-                fs::set_permissions(&phar_path, perms)
-                    .map_err(|e| format!("Failed to set {} permissions: {}", phar_name, e))?;
+                fs::set_permissions(&staged_path, perms)
+                    .map_err(|e| PhpmdLspError::Download(format!("Failed to set {} permissions: {}", phar_name, e)))?;
             }
         }
         
+        Self::clear_quarantine(&staged_path)?;
+        
+        Self::promote_download(&staging_dir, &staged_path, &phar_path)?;
+        
         Ok(phar_path)
     }
-
     
-    fn find_phpmd_config(worktree: &zed::Worktree) -> Option<String> {
-        let root_path = std::path::PathBuf::from(worktree.root_path());
+    fn staging_dir(version_dir: &str, file_name: &str) -> String {
+        format!("{}/.{}.partial", version_dir, file_name)
+    }
+    
+    fn promote_download(staging_dir: &str, staged_path: &str, final_path: &str) -> Result<(), PhpmdLspError> {
+        // Flush the extracted file before the rename makes it visible under its final name
+        fs::File::open(staged_path)
+            .and_then(|file| file.sync_all())
+            .and_then(|_| fs::rename(staged_path, final_path))
+            .map_err(|e| PhpmdLspError::Download(format!("Failed to move {} into place: {}", final_path, e)))?;
+        fs::remove_dir_all(staging_dir).ok();
         
-        for config_file in PHPMD_CONFIG_FILES {
-            let config_path = root_path.join(config_file);
-            
-            if config_path.exists() {
-                if let Some(path_str) = config_path.to_str() {
-                    return Some(path_str.to_string());
-                }
+        Ok(())
+    }
+    
+    fn write_atomically(path: &std::path::Path, contents: &str) -> std::io::Result<()> {
+        // Give every write its own temp file so concurrent writers never share one
+        static WRITE_COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(format!(".{}-{}.tmp", nonce, WRITE_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let temp_path = path.with_file_name(temp_name);
+        
+        {
+            use std::io::Write;
+            let mut file = fs::File::create(&temp_path)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+        }
+        
+        let result = fs::rename(&temp_path, path);
+        if result.is_err() {
+            fs::remove_file(&temp_path).ok();
+        }
+        result
+    }
+    
+    fn clear_quarantine(path: &str) -> Result<(), PhpmdLspError> {
+        let (os, _arch) = zed::current_platform();
+        if os != zed::Os::Mac {
+            return Ok(());
+        }
+        
+        // xattr runs on the host, so it needs the absolute path of the downloaded file
+        let absolute_path = env::current_dir()
+            .map(|dir| dir.join(path))
+            .unwrap_or_else(|_| std::path::PathBuf::from(path));
+        let absolute_path = absolute_path.to_string_lossy().to_string();
+        
+        // A missing attribute makes `xattr -d` fail, which is fine - there is nothing to clear
+        zed::process::Command::new("xattr")
+            .args(["-d", QUARANTINE_ATTRIBUTE, absolute_path.as_str()])
+            .output()
+            .ok();
+        
+        // `xattr -p` only succeeds while the attribute is still present
+        let still_quarantined = zed::process::Command::new("xattr")
+            .args(["-p", QUARANTINE_ATTRIBUTE, absolute_path.as_str()])
+            .output()
+            .map(|output| output.status == Some(0))
+            .unwrap_or(false);
+        
+        if still_quarantined {
+            return Err(PhpmdLspError::Download(Self::quarantine_remediation(&absolute_path)));
+        }
+        
+        Ok(())
+    }
+    
+    fn quarantine_remediation(path: &str) -> String {
+        format!(
+            "macOS Gatekeeper has quarantined {}. Run `xattr -d {} \"{}\"` in a terminal, then restart the PHPMD language server.",
+            path, QUARANTINE_ATTRIBUTE, path
+        )
+    }
+
+    fn phar_update_versions(phar_path: &str, env: impl FnOnce() -> Vec<(String, String)>) -> Option<(String, String)> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let mut check = fs::read_to_string(PHAR_UPDATE_CHECK_FILE)
+            .ok()
+            .and_then(|contents| zed::serde_json::from_str::<PharUpdateCheck>(&contents).ok())
+            .unwrap_or_default();
+        let mut changed = false;
+        
+        // PHARs live in versioned directories, so each path only needs to be asked for its version once
+        let installed_version = match check.installed_versions.get(phar_path) {
+            Some(version) => version.clone(),
+            None => {
+                let version = Self::phar_version(phar_path, env())?;
+                check.installed_versions.insert(phar_path.to_string(), version.clone());
+                changed = true;
+                version
+            },
+        };
+        
+        // Reuse the previous lookup if it is less than a week old
+        if check.latest_version.is_empty() || now.saturating_sub(check.checked_at) >= PHAR_UPDATE_CHECK_INTERVAL_SECS {
+            if let Some(latest_version) = Self::latest_phpmd_version() {
+                check.checked_at = now;
+                check.latest_version = latest_version;
+                changed = true;
             }
         }
         
-        None
+        if changed {
+            if let Ok(contents) = zed::serde_json::to_string(&check) {
+                Self::write_atomically(std::path::Path::new(PHAR_UPDATE_CHECK_FILE), &contents).ok();
+            }
+        }
+        
+        (!check.latest_version.is_empty()).then_some((installed_version, check.latest_version))
+    }
+    
+    fn latest_phpmd_version() -> Option<String> {
+        let release = zed::latest_github_release(
+            PHPMD_REPOSITORY,
+            zed::GithubReleaseOptions {
+                require_assets: false,
+                pre_release: false,
+            },
+        )
+        .ok()?;
+        
+        Some(release.version.trim_start_matches('v').to_string())
+    }
+    
+    fn phar_version(phar_path: &str, env: Vec<(String, String)>) -> Option<String> {
+        // php runs on the host, so it needs the absolute path of the downloaded PHAR
+        let absolute_path = env::current_dir().ok()?.join(phar_path);
+        let output = zed::process::Command::new("php")
+            .arg(absolute_path.to_string_lossy().to_string())
+            .arg("--version")
+            .envs(env)
+            .output()
+            .ok()?;
+        
+        Self::parse_phar_version(&String::from_utf8_lossy(&output.stdout))
+    }
+    
+    fn parse_phar_version(version_output: &str) -> Option<String> {
+        // PHPMD prints e.g. "PHPMD 2.15.0" (older builds: "PHPMD 2.15.0 by Manuel Pichler")
+        version_output
+            .split_whitespace()
+            .skip_while(|word| *word != "PHPMD")
+            .nth(1)
+            .map(|version| version.trim_start_matches('v'))
+            .filter(|version| version.starts_with(|c: char| c.is_ascii_digit()))
+            .map(|version| version.to_string())
+    }
+    
+    fn is_release_version(version: &str) -> bool {
+        version.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+    }
+    
+    fn is_newer_version(candidate: &str, current: &str) -> bool {
+        let parse = |version: &str| -> Vec<u64> {
+            version
+                .trim_start_matches('v')
+                .split('.')
+                .map(|part| part.parse().unwrap_or(0))
+                .collect()
+        };
+        
+        parse(candidate) > parse(current)
+    }
+    
+    fn is_single_file_worktree(root_path: &std::path::Path) -> bool {
+        // The sandbox cannot stat the root, so only roots named like PHP files are detected
+        root_path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("php"))
+    }
+    
+    fn find_phpmd_config(worktree: &zed::Worktree) -> Option<String> {
+        let root_path = std::path::PathBuf::from(worktree.root_path());
+        
+        // The sandbox cannot stat host paths, so probe through the worktree instead
+        Self::find_phpmd_config_in(&root_path, |config_file| worktree.read_text_file(config_file).is_ok())
+    }
+    
+    fn find_phpmd_config_in(root_path: &std::path::Path, is_project_file: impl Fn(&str) -> bool) -> Option<String> {
+        PHPMD_CONFIG_FILES
+            .iter()
+            .find(|config_file| is_project_file(config_file))
+            .and_then(|config_file| root_path.join(config_file).to_str().map(|path| path.to_string()))
     }
 }
 
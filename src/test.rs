@@ -0,0 +1,553 @@
+use super::*;
+use zed::serde_json::json;
+
+fn selected_source(resolution: &RulesetResolution) -> Option<&'static str> {
+    resolution
+        .trace
+        .iter()
+        .find(|entry| entry.selected)
+        .map(|entry| entry.source)
+}
+
+#[test]
+fn config_file_takes_priority_over_settings_and_env() {
+    let settings = json!({ "rulesets": "cleancode", "rulesetsMode": "replace" });
+    let resolution = PhpmdLspExtension::resolve_rulesets(
+        Some("/project/phpmd.xml".to_string()),
+        Some(&settings),
+        None,
+        Some("naming".to_string()),
+    ).unwrap();
+
+    assert_eq!(resolution.rulesets, "/project/phpmd.xml");
+    assert_eq!(selected_source(&resolution), Some("configFile"));
+    assert_eq!(resolution.trace.len(), 5);
+    assert!(resolution.merged_refs.is_empty());
+    assert_eq!(resolution.trace[1].value.as_deref(), Some("cleancode"));
+    assert!(!resolution.trace[1].selected);
+}
+
+#[test]
+fn settings_array_is_joined_and_blank_entries_skipped() {
+    let settings = json!({ "rulesets": ["cleancode", " ", "design"] });
+    let resolution = PhpmdLspExtension::resolve_rulesets(None, Some(&settings), None, None).unwrap();
+
+    assert_eq!(resolution.rulesets, "cleancode,design");
+    assert_eq!(selected_source(&resolution), Some("settings"));
+}
+
+#[test]
+fn blank_settings_fall_through_to_env() {
+    let settings = json!({ "rulesets": "   " });
+    let resolution = PhpmdLspExtension::resolve_rulesets(
+        None,
+        Some(&settings),
+        None,
+        Some("naming,unusedcode".to_string()),
+    ).unwrap();
+
+    assert_eq!(resolution.rulesets, "naming,unusedcode");
+    assert_eq!(selected_source(&resolution), Some("env"));
+    assert_eq!(resolution.trace[1].value, None);
+}
+
+#[test]
+fn defaults_used_when_nothing_configured() {
+    let resolution = PhpmdLspExtension::resolve_rulesets(None, None, None, Some(String::new())).unwrap();
+
+    assert_eq!(resolution.rulesets, DEFAULT_RULESETS);
+    assert_eq!(selected_source(&resolution), Some("default"));
+}
+
+#[test]
+fn config_trace_serializes_with_camel_case_sources() {
+    let resolution = PhpmdLspExtension::resolve_rulesets(None, None, None, None).unwrap();
+    let trace = zed::serde_json::to_value(&resolution.trace).unwrap();
+
+    assert_eq!(trace[0], json!({ "source": "configFile", "value": null, "selected": false }));
+    assert_eq!(trace[4], json!({ "source": "default", "value": DEFAULT_RULESETS, "selected": true }));
+}
+
+#[test]
+fn newer_phpmd_release_is_detected() {
+    assert!(PhpmdLspExtension::is_newer_version("2.15.1", "2.15.0"));
+    assert!(PhpmdLspExtension::is_newer_version("v3.0.0", "2.15.0"));
+    assert!(PhpmdLspExtension::is_newer_version("2.16", "2.15.0"));
+}
+
+#[test]
+fn same_or_older_phpmd_release_is_not_an_update() {
+    assert!(!PhpmdLspExtension::is_newer_version("2.15.0", "2.15.0"));
+    assert!(!PhpmdLspExtension::is_newer_version("2.14.9", "2.15.0"));
+}
+
+#[test]
+fn phar_version_is_read_from_version_output() {
+    assert_eq!(PhpmdLspExtension::parse_phar_version("PHPMD 2.15.0\n"), Some("2.15.0".to_string()));
+    assert_eq!(
+        PhpmdLspExtension::parse_phar_version("PHPMD 2.13.0 by Manuel Pichler\n"),
+        Some("2.13.0".to_string())
+    );
+    assert_eq!(PhpmdLspExtension::parse_phar_version("Could not open input file"), None);
+}
+
+#[test]
+fn phar_update_check_without_installed_versions_still_parses() {
+    let check: PharUpdateCheck =
+        zed::serde_json::from_str(r#"{ "checkedAt": 1700000000, "latestVersion": "2.15.0" }"#).unwrap();
+    assert_eq!(check.latest_version, "2.15.0");
+    assert!(check.installed_versions.is_empty());
+
+    let check = PharUpdateCheck {
+        installed_versions: BTreeMap::from([("phpmd-0.1.0/phpmd.phar".to_string(), "2.15.0".to_string())]),
+        ..PharUpdateCheck::default()
+    };
+    assert_eq!(
+        zed::serde_json::to_value(&check).unwrap()["installedVersions"],
+        json!({ "phpmd-0.1.0/phpmd.phar": "2.15.0" })
+    );
+}
+
+#[test]
+fn phar_version_setting_only_accepts_release_versions() {
+    assert!(PhpmdLspExtension::is_release_version("2.15.0"));
+    assert!(PhpmdLspExtension::is_release_version("3.0"));
+    assert!(!PhpmdLspExtension::is_release_version("2.15.0/../../evil"));
+    assert!(!PhpmdLspExtension::is_release_version("latest"));
+    assert!(!PhpmdLspExtension::is_release_version("2..0"));
+}
+
+#[test]
+fn errors_convert_to_categorized_strings() {
+    let message: String = PhpmdLspError::Download("release 0.1.0 has no assets".to_string()).into();
+    assert_eq!(message, "Download error: release 0.1.0 has no assets");
+
+    let message: String = PhpmdLspError::Config("unknown language server: foo".to_string()).into();
+    assert_eq!(message, "Configuration error: unknown language server: foo");
+}
+
+#[test]
+fn settings_toggle_map_keeps_enabled_rulesets() {
+    let settings = json!({
+        "rulesets": {
+            "cleancode": true,
+            "controversial": false,
+            "design": true,
+            "naming": "yes"
+        }
+    });
+    let resolution = PhpmdLspExtension::resolve_rulesets(None, Some(&settings), None, None).unwrap();
+
+    assert_eq!(resolution.rulesets, "cleancode,design");
+    assert_eq!(selected_source(&resolution), Some("settings"));
+}
+
+#[test]
+fn settings_toggle_map_with_nothing_enabled_falls_back_to_defaults() {
+    let settings = json!({ "rulesets": { "cleancode": false } });
+    let resolution = PhpmdLspExtension::resolve_rulesets(None, Some(&settings), None, None).unwrap();
+
+    assert_eq!(resolution.rulesets, DEFAULT_RULESETS);
+    assert_eq!(selected_source(&resolution), Some("default"));
+}
+
+#[test]
+fn user_ruleset_path_prefers_xdg_config_home() {
+    let shell_env = vec![
+        ("HOME".to_string(), "/home/dev".to_string()),
+        ("XDG_CONFIG_HOME".to_string(), "/custom/config".to_string()),
+    ];
+    assert_eq!(
+        PhpmdLspExtension::user_ruleset_path(&shell_env, false),
+        Some(std::path::PathBuf::from("/custom/config/phpmd-lsp/ruleset.xml"))
+    );
+
+    let shell_env = vec![("HOME".to_string(), "/home/dev".to_string())];
+    assert_eq!(
+        PhpmdLspExtension::user_ruleset_path(&shell_env, false),
+        Some(std::path::PathBuf::from("/home/dev/.config/phpmd-lsp/ruleset.xml"))
+    );
+
+    assert_eq!(PhpmdLspExtension::user_ruleset_path(&[], false), None);
+}
+
+#[test]
+fn user_ruleset_path_uses_appdata_on_windows() {
+    let shell_env = vec![
+        ("USERPROFILE".to_string(), r"C:\Users\dev".to_string()),
+        ("APPDATA".to_string(), r"C:\Users\dev\AppData\Roaming".to_string()),
+    ];
+    assert_eq!(
+        PhpmdLspExtension::user_ruleset_path(&shell_env, true),
+        Some(std::path::PathBuf::from(r"C:\Users\dev\AppData\Roaming").join("phpmd-lsp/ruleset.xml"))
+    );
+
+    let shell_env = vec![("USERPROFILE".to_string(), r"C:\Users\dev".to_string())];
+    assert_eq!(PhpmdLspExtension::user_ruleset_path(&shell_env, true), None);
+}
+
+#[test]
+fn combined_ruleset_references_each_layer_in_order() {
+    let xml = PhpmdLspExtension::combined_ruleset_xml(&[
+        "/home/dev/ruleset.xml".to_string(),
+        "/project/R&D/phpmd.xml".to_string(),
+    ]);
+
+    let user_ref = xml.find("<rule ref=\"/home/dev/ruleset.xml\"/>").unwrap();
+    let project_ref = xml.find("<rule ref=\"/project/R&amp;D/phpmd.xml\"/>").unwrap();
+    assert!(user_ref < project_ref);
+    assert!(xml.starts_with("<?xml version=\"1.0\"?>"));
+    assert!(xml.trim_end().ends_with("</ruleset>"));
+}
+
+#[test]
+fn combined_ruleset_file_is_unique_per_worktree() {
+    assert_ne!(
+        PhpmdLspExtension::combined_ruleset_file(1),
+        PhpmdLspExtension::combined_ruleset_file(2)
+    );
+    assert_eq!(PhpmdLspExtension::combined_ruleset_file(7), "phpmd-combined-ruleset-7.xml");
+}
+
+#[test]
+fn project_config_replaces_settings_rulesets_by_default() {
+    let settings = json!({ "rulesets": "cleancode" });
+    let resolution = PhpmdLspExtension::resolve_rulesets(
+        Some("/project/phpmd.xml".to_string()),
+        Some(&settings),
+        None,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(resolution.rulesets, "/project/phpmd.xml");
+    assert!(resolution.merged_refs.is_empty());
+    assert!(!resolution.trace[1].selected);
+}
+
+#[test]
+fn settings_rulesets_are_merged_with_project_config_on_request() {
+    let settings = json!({ "rulesets": "cleancode, ./custom/rules.xml", "rulesetsMode": "merge" });
+    let resolution = PhpmdLspExtension::resolve_rulesets(
+        Some("/project/phpmd.xml".to_string()),
+        Some(&settings),
+        None,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(
+        resolution.merged_refs,
+        vec![
+            "rulesets/cleancode.xml".to_string(),
+            "./custom/rules.xml".to_string(),
+            "/project/phpmd.xml".to_string(),
+        ]
+    );
+    assert!(resolution.trace[1].selected);
+}
+
+#[test]
+fn unknown_rulesets_mode_is_rejected() {
+    let settings = json!({ "rulesets": "cleancode", "rulesetsMode": "merg" });
+    let result = PhpmdLspExtension::resolve_rulesets(None, Some(&settings), None, None);
+
+    assert!(matches!(result, Err(PhpmdLspError::Config(ref message)) if message.contains("\"merg\"")));
+}
+
+#[test]
+fn quarantine_remediation_includes_copyable_command() {
+    let message = PhpmdLspExtension::quarantine_remediation("/Users/dev/Library/phpmd-0.1.0/phpmd.phar");
+
+    assert!(message.contains(
+        "`xattr -d com.apple.quarantine \"/Users/dev/Library/phpmd-0.1.0/phpmd.phar\"`"
+    ));
+}
+
+#[test]
+fn linux_binary_name_selects_musl_variant() {
+    assert_eq!(
+        PhpmdLspServer::platform_binary_name(zed::Os::Linux, zed::Architecture::X8664, true),
+        "phpmd-lsp-server-linux-x64-musl"
+    );
+    assert_eq!(
+        PhpmdLspServer::platform_binary_name(zed::Os::Linux, zed::Architecture::Aarch64, true),
+        "phpmd-lsp-server-linux-arm64-musl"
+    );
+    assert_eq!(
+        PhpmdLspServer::platform_binary_name(zed::Os::Linux, zed::Architecture::Aarch64, false),
+        "phpmd-lsp-server-linux-arm64"
+    );
+    assert_eq!(
+        PhpmdLspServer::platform_binary_name(zed::Os::Mac, zed::Architecture::Aarch64, true),
+        "phpmd-lsp-server-macos-arm64"
+    );
+}
+
+#[test]
+fn missing_version_manager_shims_go_first_on_path() {
+    let shell_env = vec![
+        ("PATH".to_string(), "/usr/bin:/home/dev/.asdf/shims".to_string()),
+        ("HOME".to_string(), "/home/dev".to_string()),
+        ("MISE_DATA_DIR".to_string(), "/opt/mise".to_string()),
+    ];
+    let env = PhpmdLspServer::env_with_shims_on_path(shell_env, false);
+
+    assert_eq!(
+        env,
+        vec![
+            ("PATH".to_string(), "/opt/mise/shims:/home/dev/.phpenv/shims:/usr/bin:/home/dev/.asdf/shims".to_string()),
+            ("HOME".to_string(), "/home/dev".to_string()),
+            ("MISE_DATA_DIR".to_string(), "/opt/mise".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn windows_path_variable_keeps_its_spelling_and_system_directories() {
+    let shell_env = vec![
+        ("Path".to_string(), r"C:\Windows\system32;C:\php".to_string()),
+        ("USERPROFILE".to_string(), r"C:\Users\dev".to_string()),
+        ("ASDF_DATA_DIR".to_string(), r"C:\asdf".to_string()),
+        ("MISE_DATA_DIR".to_string(), r"C:\mise".to_string()),
+        ("PHPENV_ROOT".to_string(), r"C:\phpenv".to_string()),
+    ];
+    let env = PhpmdLspServer::env_with_shims_on_path(shell_env, true);
+
+    let path_entries: Vec<_> = env.iter().filter(|(name, _)| name.eq_ignore_ascii_case("PATH")).collect();
+    assert_eq!(path_entries.len(), 1);
+    assert_eq!(path_entries[0].0, "Path");
+    assert!(path_entries[0].1.ends_with(r";C:\Windows\system32;C:\php"));
+    assert_eq!(path_entries[0].1.split(';').count(), 5);
+}
+
+#[test]
+fn config_discovery_follows_filename_priority() {
+    let root = std::path::Path::new("/project");
+
+    assert_eq!(
+        PhpmdLspExtension::find_phpmd_config_in(root, |file| file == "phpmd.xml.dist" || file == ".phpmd.xml"),
+        Some(root.join("phpmd.xml.dist").to_string_lossy().to_string())
+    );
+    assert_eq!(PhpmdLspExtension::find_phpmd_config_in(root, |_| false), None);
+}
+
+#[test]
+fn user_settings_override_project_overrides() {
+    let project = PhpmdLspExtension::parse_project_overrides(
+        r#"{ "rulesets": "cleancode", "maxConcurrent": 2 }"#,
+    )
+    .unwrap();
+    let user_settings = json!({ "rulesets": ["naming"], "logLevel": "debug" });
+
+    let merged = PhpmdLspExtension::merge_settings(project, Some(&user_settings));
+    assert_eq!(
+        zed::serde_json::Value::Object(merged),
+        json!({ "rulesets": ["naming"], "maxConcurrent": 2, "logLevel": "debug" })
+    );
+}
+
+#[test]
+fn project_overrides_cannot_pick_what_runs_on_this_machine() {
+    let project = PhpmdLspExtension::parse_project_overrides(
+        r#"{ "rulesets": "cleancode", "phpmdPath": "/tmp/evil.phar", "channel": "local", "pharVersion": "2.15.0", "pharError": "x", "singleFile": true }"#,
+    )
+    .unwrap();
+
+    assert_eq!(zed::serde_json::Value::Object(project), json!({ "rulesets": "cleancode" }));
+}
+
+#[test]
+fn project_overrides_rulesets_are_traced_separately_from_settings() {
+    let overrides = json!({ "rulesets": "design" });
+    let resolution = PhpmdLspExtension::resolve_rulesets(None, None, Some(&overrides), None).unwrap();
+
+    assert_eq!(resolution.rulesets, "design");
+    assert_eq!(selected_source(&resolution), Some("projectOverrides"));
+    assert_eq!(resolution.trace[1].value, None);
+
+    let settings = json!({ "rulesets": "naming" });
+    let resolution = PhpmdLspExtension::resolve_rulesets(None, Some(&settings), Some(&overrides), None).unwrap();
+
+    assert_eq!(resolution.rulesets, "naming");
+    assert_eq!(selected_source(&resolution), Some("settings"));
+    assert_eq!(resolution.trace[2].value.as_deref(), Some("design"));
+}
+
+#[test]
+fn project_overrides_must_be_a_json_object() {
+    assert!(matches!(
+        PhpmdLspExtension::parse_project_overrides("[\"cleancode\"]"),
+        Err(PhpmdLspError::Config(_))
+    ));
+    assert!(matches!(
+        PhpmdLspExtension::parse_project_overrides("{ not json"),
+        Err(PhpmdLspError::Config(_))
+    ));
+}
+
+#[test]
+fn single_file_worktree_is_detected_by_php_extension() {
+    assert!(PhpmdLspExtension::is_single_file_worktree(std::path::Path::new("/projects/app/script.php")));
+    assert!(PhpmdLspExtension::is_single_file_worktree(std::path::Path::new("/remote/Legacy.PHP")));
+    assert!(!PhpmdLspExtension::is_single_file_worktree(std::path::Path::new("/projects/app")));
+    assert!(!PhpmdLspExtension::is_single_file_worktree(std::path::Path::new("/projects/app/bin/console")));
+}
+
+#[test]
+fn atomic_write_replaces_contents_without_leftovers() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("phpmd-combined-ruleset.xml");
+    fs::write(&path, "old").unwrap();
+
+    PhpmdLspExtension::write_atomically(&path, "new").unwrap();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn promoted_download_moves_into_version_dir_and_cleans_staging() {
+    let dir = tempfile::tempdir().unwrap();
+    let version_dir = dir.path().join("phpmd-0.1.0").to_string_lossy().to_string();
+    let staging_dir = PhpmdLspExtension::staging_dir(&version_dir, "phpmd.phar");
+    let staged_path = format!("{}/phpmd.phar", staging_dir);
+    let final_path = format!("{}/phpmd.phar", version_dir);
+    fs::create_dir_all(&staging_dir).unwrap();
+    fs::write(&staged_path, "phar").unwrap();
+
+    PhpmdLspExtension::promote_download(&staging_dir, &staged_path, &final_path).unwrap();
+
+    assert_eq!(fs::read_to_string(&final_path).unwrap(), "phar");
+    assert!(fs::metadata(&staging_dir).is_err());
+}
+
+#[test]
+fn sanitize_rulesets_keeps_builtins_and_resolves_paths_with_spaces_and_unicode() {
+    let root = std::path::Path::new("/projects/my app");
+
+    let sanitized = PhpmdLspExtension::sanitize_rulesets(
+        "cleancode, rulesets/naming.xml ,my rules/règles-équipe.xml",
+        root,
+        |path| path == "my rules/règles-équipe.xml",
+    )
+    .unwrap();
+
+    assert_eq!(
+        sanitized,
+        format!("cleancode,rulesets/naming.xml,{}", root.join("my rules/règles-équipe.xml").to_string_lossy())
+    );
+}
+
+#[test]
+fn sanitize_rulesets_passes_paths_outside_the_worktree_through() {
+    let sanitized = PhpmdLspExtension::sanitize_rulesets(
+        r"/etc/phpmd/team.xml,C:\Rules\team.xml,\\fileserver\share\phpmd.xml,../shared/phpmd.xml",
+        std::path::Path::new("/project"),
+        |_| false,
+    )
+    .unwrap();
+
+    assert_eq!(
+        sanitized,
+        r"/etc/phpmd/team.xml,C:\Rules\team.xml,\\fileserver\share\phpmd.xml,../shared/phpmd.xml"
+    );
+}
+
+#[test]
+fn sanitize_rulesets_skips_empty_entries() {
+    let sanitized = PhpmdLspExtension::sanitize_rulesets(" cleancode,,design, ", std::path::Path::new("/project"), |_| true).unwrap();
+
+    assert_eq!(sanitized, "cleancode,design");
+}
+
+#[test]
+fn sanitize_rulesets_rejects_nul_bytes_and_missing_files() {
+    let root = std::path::Path::new("/project");
+
+    let error = PhpmdLspExtension::sanitize_rulesets("clean\0code", root, |_| true).unwrap_err();
+    assert!(matches!(error, PhpmdLspError::Config(ref message) if message.contains("NUL")));
+
+    let error = PhpmdLspExtension::sanitize_rulesets("cleancode,missing.xml", root, |_| false).unwrap_err();
+    assert!(matches!(error, PhpmdLspError::Config(ref message) if message.contains("\"missing.xml\" does not exist")));
+}
+
+#[test]
+fn only_rulesets_in_use_are_sanitized() {
+    let root = std::path::Path::new("/project");
+    let settings = json!({ "rulesets": "missing.xml" });
+
+    // A project config replaces the settings rulesets, so their missing file doesn't matter
+    let mut resolution = PhpmdLspExtension::resolve_rulesets(
+        Some("/project/phpmd.xml".to_string()),
+        Some(&settings),
+        None,
+        None,
+    )
+    .unwrap();
+    PhpmdLspExtension::sanitize_resolution(&mut resolution, root, |_| false).unwrap();
+    assert_eq!(resolution.rulesets, "/project/phpmd.xml");
+
+    let mut resolution = PhpmdLspExtension::resolve_rulesets(None, Some(&settings), None, None).unwrap();
+    assert!(PhpmdLspExtension::sanitize_resolution(&mut resolution, root, |_| false).is_err());
+}
+
+#[test]
+fn merged_settings_rulesets_are_sanitized() {
+    let settings = json!({ "rulesets": "cleancode,,custom/rules.xml,", "rulesetsMode": "merge" });
+    let mut resolution = PhpmdLspExtension::resolve_rulesets(
+        Some("/project/phpmd.xml".to_string()),
+        Some(&settings),
+        None,
+        None,
+    )
+    .unwrap();
+    PhpmdLspExtension::sanitize_resolution(&mut resolution, std::path::Path::new("/project"), |_| true).unwrap();
+
+    assert_eq!(
+        resolution.merged_refs,
+        vec![
+            "rulesets/cleancode.xml".to_string(),
+            "/project/custom/rules.xml".to_string(),
+            "/project/phpmd.xml".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn binary_channel_defaults_to_stable() {
+    assert_eq!(BinaryChannel::from_settings(None), Ok(BinaryChannel::Stable));
+    assert_eq!(
+        BinaryChannel::from_settings(Some(&json!({ "rulesets": "cleancode" }))),
+        Ok(BinaryChannel::Stable)
+    );
+    assert_eq!(
+        BinaryChannel::from_settings(Some(&json!({ "channel": "nightly" }))),
+        Ok(BinaryChannel::Nightly)
+    );
+}
+
+#[test]
+fn local_binary_channel_requires_a_path() {
+    assert_eq!(
+        BinaryChannel::from_settings(Some(&json!({
+            "channel": "local",
+            "localBinaryPath": "/src/zed-phpmd-lsp/lsp-server/target/release/phpmd-lsp-server"
+        }))),
+        Ok(BinaryChannel::Local(
+            "/src/zed-phpmd-lsp/lsp-server/target/release/phpmd-lsp-server".to_string()
+        ))
+    );
+    assert!(matches!(
+        BinaryChannel::from_settings(Some(&json!({ "channel": "local" }))),
+        Err(PhpmdLspError::Config(_))
+    ));
+    assert!(matches!(
+        BinaryChannel::from_settings(Some(&json!({ "channel": "local", "localBinaryPath": "/tmp/phpmd\u{0}" }))),
+        Err(PhpmdLspError::Config(ref message)) if message.contains("NUL")
+    ));
+    assert!(matches!(
+        BinaryChannel::from_settings(Some(&json!({ "channel": "beta" }))),
+        Err(PhpmdLspError::Config(_))
+    ));
+}